@@ -0,0 +1,368 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use super::command::GitCommand;
+use super::premirror::{self, MirrorRule};
+use super::{absolute, persist, staging_dir, Error, GitCredentialStore, Repository, Result};
+
+/// The revision a [`Checkout`] should end up at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Revision {
+    /// The default branch of the remote.
+    Default,
+    /// The tip of a remote branch, as of the last fetch.
+    Branch(String),
+    /// A fixed commit.
+    Commit(String),
+}
+
+/// Brings a working tree in sync with a remote repository.
+///
+/// If the destination does not exist yet, or is an empty directory, it is cloned from the URL.
+/// Otherwise it must be a repository rooted at the destination itself; its `origin` is pointed
+/// at the URL if it has changed and then fetched. In both cases the requested [`Revision`] is
+/// checked out as a detached `HEAD`, so the work tree never depends on what local branches
+/// happen to exist.
+///
+/// ```no_run
+/// # async fn example() -> core_vcs::git::Result<()> {
+/// use core_vcs::git::{Checkout, Revision};
+///
+/// let repo = Checkout::new("https://git.yoctoproject.org/poky", "layers/poky")
+///     .revision(Revision::Branch("kirkstone".to_owned()))
+///     .reference("/srv/git-mirrors/poky.git")
+///     .run()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Checkout {
+    url: String,
+    path: PathBuf,
+    revision: Revision,
+    reference: Option<PathBuf>,
+    credentials: Option<GitCredentialStore>,
+    timeout: Option<Duration>,
+    deadline: Option<Duration>,
+    depth: Option<u32>,
+    mirrors: Vec<MirrorRule>,
+}
+
+impl Checkout {
+    /// Checks out the default branch of `url` into `path`.
+    pub fn new(url: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            url: url.into(),
+            path: path.into(),
+            revision: Revision::Default,
+            reference: None,
+            credentials: None,
+            timeout: None,
+            deadline: None,
+            depth: None,
+            mirrors: Vec::new(),
+        }
+    }
+
+    /// Sets the revision to check out.
+    pub fn revision(mut self, revision: Revision) -> Self {
+        self.revision = revision;
+        self
+    }
+
+    /// Borrows objects from a local clone of the same repository when cloning.
+    ///
+    /// The reference is passed to `git clone --reference-if-able`, so a missing reference
+    /// repository falls back to a plain clone. It has no effect when the destination already
+    /// exists.
+    pub fn reference(mut self, path: impl Into<PathBuf>) -> Self {
+        self.reference = Some(path.into());
+        self
+    }
+
+    /// Authenticates the clone or fetch from an HTTPS or SSH remote with `credentials`.
+    pub fn credentials(mut self, credentials: GitCredentialStore) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Clones from a premirror of the URL when one is available.
+    ///
+    /// The rules are tried in order, and the first premirror that answers a
+    /// [`Remote::probe`](super::Remote::probe) is cloned from; if none does, or the clone from
+    /// it fails, the URL itself is cloned. Either way `origin` ends up pointing at the URL, so
+    /// later fetches go upstream, as with kas's `KAS_PREMIRRORS`. Has no effect when the
+    /// destination already exists.
+    pub fn mirrors(mut self, rules: Vec<MirrorRule>) -> Self {
+        self.mirrors = rules;
+        self
+    }
+
+    /// Fetches only the last `depth` commits of the requested revision.
+    ///
+    /// A shallow clone fetches only the requested branch. A pinned commit is fetched by hash,
+    /// which the remote must allow. Protocol v2 servers do this by default.
+    pub fn depth(mut self, depth: u32) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Kills the clone or fetch if it takes longer than `timeout`.
+    ///
+    /// Each network operation gets the full `timeout`; use [`Checkout::deadline`] to bound the
+    /// checkout as a whole.
+    ///
+    /// The error is reported as [`Error::Timeout`](super::Error::Timeout), whose command line
+    /// names the URL or the repository that hung.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Cancels the whole checkout if it takes longer than `deadline`.
+    ///
+    /// Unlike [`Checkout::timeout`], which limits each network operation on its own, this
+    /// bounds everything [`Checkout::run`] does, including probing premirrors and checking out
+    /// the work tree. Cancelling kills the git process running at the time and is reported as
+    /// [`Error::Timeout`](super::Error::Timeout) naming the URL and the destination. A clone
+    /// that is cancelled leaves nothing behind, since it is staged.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Clones or fetches the repository and checks out the requested revision.
+    ///
+    /// A pinned commit that is already present locally is checked out without contacting the
+    /// remote. A non-empty destination that is not a repository of its own fails with
+    /// [`Error::NotARepository`] and is left untouched.
+    pub async fn run(&self) -> Result<Repository> {
+        let Some(deadline) = self.deadline else {
+            return self.sync().await;
+        };
+
+        tokio::time::timeout(deadline, self.sync())
+            .await
+            .map_err(|_| Error::Timeout {
+                command: format!("checkout of {} into {}", self.url, self.path.display()),
+                timeout: deadline,
+            })?
+    }
+
+    /// Does the work of [`Checkout::run`], without the deadline.
+    async fn sync(&self) -> Result<Repository> {
+        let path = absolute(&self.path)?;
+        let existing = !is_missing_or_empty(&path).await?;
+        let repo = if existing {
+            let repo = self.configure(Repository::open_exact(&path).await?);
+            self.set_origin(&repo).await?;
+            repo
+        } else {
+            self.clone_into(&path).await?
+        };
+
+        let target = match &self.revision {
+            Revision::Default => {
+                let target = "refs/remotes/origin/HEAD";
+                if existing {
+                    repo.fetch_with(None, self.depth).await?;
+                    // Only `git clone` records the remote's default branch; a repository set
+                    // up any other way has to ask for it.
+                    if let Err(Error::Command { .. }) = repo.rev_parse(target).await {
+                        self.set_head(&repo).await?;
+                    }
+                }
+                target.to_owned()
+            }
+            Revision::Branch(branch) => {
+                let tracking = format!("refs/remotes/origin/{branch}");
+                if existing {
+                    let refspec = format!("+refs/heads/{branch}:{tracking}");
+                    repo.fetch_with(Some(&refspec), self.depth).await?;
+                }
+                tracking
+            }
+            Revision::Commit(commit) => {
+                match repo.rev_parse(commit).await {
+                    Ok(_) => {}
+                    Err(Error::Command { .. }) => {
+                        repo.fetch_with(Some(commit), self.depth).await?;
+                    }
+                    Err(err) => return Err(err),
+                }
+                commit.clone()
+            }
+        };
+        repo.checkout(&target).await?;
+
+        Ok(repo)
+    }
+
+    /// Runs several checkouts concurrently, at most `jobs` at a time.
+    ///
+    /// Returns one result per checkout, in the order they were given; a failing repository does
+    /// not stop the others. A `jobs` of zero is treated as one. Must be called from within a
+    /// Tokio runtime, since each checkout runs as its own task.
+    pub async fn run_all(
+        checkouts: impl IntoIterator<Item = Checkout>,
+        jobs: usize,
+    ) -> Vec<Result<Repository>> {
+        let permits = Arc::new(Semaphore::new(jobs.max(1)));
+        let mut tasks = JoinSet::new();
+        let mut count = 0;
+        for (i, checkout) in checkouts.into_iter().enumerate() {
+            let permits = Arc::clone(&permits);
+            tasks.spawn(async move {
+                let _permit = permits.acquire().await.expect("semaphore is never closed");
+                (i, checkout.run().await)
+            });
+            count += 1;
+        }
+
+        let mut results: Vec<Option<Result<Repository>>> = (0..count).map(|_| None).collect();
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok((i, result)) => results[i] = Some(result),
+                Err(err) => std::panic::resume_unwind(err.into_panic()),
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.expect("every task reports a result"))
+            .collect()
+    }
+
+    /// Clones into `path`, which must not exist or be an empty directory.
+    ///
+    /// The clone is staged next to `path` and only moved into place once it has completed, so a
+    /// failed, timed-out or cancelled clone leaves `path` as it was.
+    async fn clone_into(&self, path: &Path) -> Result<Repository> {
+        let credentials = self.credentials.as_ref();
+        if let Some(mirror) =
+            premirror::reachable(&self.mirrors, &self.url, self.timeout, credentials).await
+        {
+            match self.clone_from(&mirror, path).await {
+                Err(Error::Command { .. } | Error::Timeout { .. }) => {}
+                result => return result,
+            }
+        }
+
+        self.clone_from(&self.url, path).await
+    }
+
+    /// Clones `url` into `path`, leaving `origin` pointing at the configured URL.
+    async fn clone_from(&self, url: &str, path: &Path) -> Result<Repository> {
+        let staging = staging_dir(path).await?;
+        let staged = staging.path().join("repo");
+        let mut clone = GitCommand::new()
+            .timeout(self.timeout)
+            .credentials(self.credentials.as_ref())?
+            .args(["clone", "--quiet", "--no-checkout"]);
+        if let Some(reference) = &self.reference {
+            clone = clone.arg("--reference-if-able").arg(reference);
+        }
+        if let Some(depth) = self.depth {
+            clone = clone.arg(format!("--depth={depth}"));
+            if let Revision::Branch(branch) = &self.revision {
+                clone = clone.args(["--branch", branch]);
+            }
+        }
+        clone.arg("--").arg(url).arg(&staged).output().await?;
+        if url != self.url {
+            GitCommand::new()
+                .current_dir(&staged)
+                .args(["remote", "set-url", "origin", "--"])
+                .arg(&self.url)
+                .output()
+                .await?;
+        }
+        persist(&staged, path).await?;
+
+        Ok(self.configure(Repository::open(path).await?))
+    }
+
+    /// Points `origin` of an existing checkout at the configured URL.
+    ///
+    /// The configured URL is compared as written, before any `url.<base>.insteadOf` rewrites,
+    /// so a rewrite in the user's configuration does not make it look changed on every run.
+    /// When the URL has changed the remote's default branch is looked up again, since the
+    /// `origin/HEAD` recorded for the old URL may no longer exist.
+    async fn set_origin(&self, repo: &Repository) -> Result<()> {
+        let current = repo
+            .git()
+            .args(["config", "--get", "remote.origin.url"])
+            .output()
+            .await;
+        match current {
+            Ok(url) if url.trim_end() == self.url => return Ok(()),
+            Ok(_) => {
+                repo.git()
+                    .args(["remote", "set-url", "origin", "--"])
+                    .arg(&self.url)
+                    .output()
+                    .await?;
+            }
+            Err(Error::Command { .. }) => {
+                repo.git()
+                    .args(["remote", "add", "--", "origin"])
+                    .arg(&self.url)
+                    .output()
+                    .await?;
+            }
+            Err(err) => return Err(err),
+        }
+
+        if self.revision == Revision::Default {
+            self.set_head(repo).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Records the remote's default branch as `origin/HEAD`.
+    async fn set_head(&self, repo: &Repository) -> Result<()> {
+        repo.git()
+            .timeout(self.timeout)
+            .credentials(self.credentials.as_ref())?
+            .args(["remote", "set-head", "origin", "--auto"])
+            .output()
+            .await
+            .map(drop)
+    }
+
+    /// Applies the network settings of this checkout to `repo`.
+    ///
+    /// The clone depth is not among them: it is passed to each fetch explicitly.
+    fn configure(&self, mut repo: Repository) -> Repository {
+        if let Some(credentials) = &self.credentials {
+            repo = repo.with_credentials(credentials.clone());
+        }
+        if let Some(timeout) = self.timeout {
+            repo = repo.with_timeout(timeout);
+        }
+        repo
+    }
+}
+
+/// Returns `true` if nothing exists at `path` or it is an empty directory, i.e. if it can be
+/// cloned into.
+async fn is_missing_or_empty(path: &Path) -> Result<bool> {
+    if !path.exists() {
+        return Ok(true);
+    }
+    if !path.is_dir() {
+        return Ok(false);
+    }
+
+    Ok(tokio::fs::read_dir(path)
+        .await?
+        .next_entry()
+        .await?
+        .is_none())
+}
@@ -0,0 +1,21 @@
+/// A commit as reported by `git log`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    /// The full commit hash.
+    pub id: String,
+    /// The first line of the commit message.
+    pub summary: String,
+}
+
+impl Commit {
+    /// The `--format` string whose output [`Commit::parse`] understands.
+    pub(crate) const FORMAT: &'static str = "--format=%H%x00%s";
+
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let (id, summary) = line.split_once('\0')?;
+        Some(Self {
+            id: id.to_owned(),
+            summary: summary.to_owned(),
+        })
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+use tempfile::NamedTempFile;
+
+use super::Result;
+
+/// Credentials for HTTPS and SSH remotes.
+///
+/// HTTPS remotes get per-host username/token pairs, handed to git through a private
+/// `git credential-store` file that only exists while a command runs, so tokens never appear
+/// in URLs, command lines or the repository configuration. SSH remotes get a private key and,
+/// optionally, the known host keys to accept, passed to `ssh` through `GIT_SSH_COMMAND`; key
+/// material and host keys given inline are likewise written to private temporary files. The
+/// [`Debug`](std::fmt::Debug) output lists hosts, usernames and file paths but never tokens or
+/// key material.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct GitCredentialStore {
+    hosts: BTreeMap<String, Credential>,
+    ssh_key: Option<Source>,
+    known_hosts: Option<Source>,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct Credential {
+    username: String,
+    token: String,
+}
+
+/// Where an SSH key or a list of known hosts comes from.
+#[derive(Clone, PartialEq, Eq)]
+enum Source {
+    File(PathBuf),
+    Inline(String),
+}
+
+/// The temporary files behind a [`GitCredentialStore`], alive for as long as one git command
+/// runs. They are removed when this is dropped.
+pub(crate) struct CredentialFiles {
+    store: Option<NamedTempFile>,
+    ssh_key: Option<NamedTempFile>,
+    known_hosts: Option<NamedTempFile>,
+}
+
+impl GitCredentialStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses `username` and `token` for every HTTPS remote on `host`.
+    ///
+    /// `host` may include a port (`git.example.com:8443`). Inserting the same host again
+    /// replaces the previous credentials.
+    pub fn insert(
+        &mut self,
+        host: impl Into<String>,
+        username: impl Into<String>,
+        token: impl Into<String>,
+    ) {
+        self.hosts.insert(
+            host.into(),
+            Credential {
+                username: username.into(),
+                token: token.into(),
+            },
+        );
+    }
+
+    /// Authenticates to SSH remotes with the private key in the file at `path`, like kas's
+    /// `SSH_PRIVATE_KEY_FILE`.
+    ///
+    /// Only this key is offered to the server (`IdentitiesOnly=yes`). Replaces any key set
+    /// before.
+    pub fn set_ssh_key_file(&mut self, path: impl Into<PathBuf>) {
+        self.ssh_key = Some(Source::File(path.into()));
+    }
+
+    /// Authenticates to SSH remotes with the private key `key`, given in OpenSSH or PEM format,
+    /// like kas's `SSH_PRIVATE_KEY`.
+    ///
+    /// Only this key is offered to the server (`IdentitiesOnly=yes`). Replaces any key set
+    /// before.
+    pub fn set_ssh_key(&mut self, key: impl Into<String>) {
+        self.ssh_key = Some(Source::Inline(key.into()));
+    }
+
+    /// Accepts only the SSH host keys listed in the `known_hosts` file at `path`.
+    ///
+    /// Hosts not in the file are rejected rather than trusted on first use. Replaces any known
+    /// hosts set before.
+    pub fn set_known_hosts_file(&mut self, path: impl Into<PathBuf>) {
+        self.known_hosts = Some(Source::File(path.into()));
+    }
+
+    /// Accepts only the SSH host keys in `entries`, given in `known_hosts` format, e.g. the
+    /// output of `ssh-keyscan`.
+    ///
+    /// Hosts not listed are rejected rather than trusted on first use. Replaces any known
+    /// hosts set before.
+    pub fn set_known_hosts(&mut self, entries: impl Into<String>) {
+        self.known_hosts = Some(Source::Inline(entries.into()));
+    }
+
+    /// Returns `true` if the store holds no credentials and no SSH settings.
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.ssh_key.is_none() && self.known_hosts.is_none()
+    }
+
+    /// Writes the HTTPS credentials in `git credential-store` format, and any inline SSH key or
+    /// known hosts, to files readable only by the current user.
+    pub(crate) fn write(&self) -> Result<CredentialFiles> {
+        let store = if self.hosts.is_empty() {
+            None
+        } else {
+            let mut file = NamedTempFile::new()?;
+            for (host, credential) in &self.hosts {
+                writeln!(
+                    file,
+                    "https://{}:{}@{host}",
+                    percent_encode(&credential.username),
+                    percent_encode(&credential.token),
+                )?;
+            }
+            file.flush()?;
+            Some(file)
+        };
+
+        Ok(CredentialFiles {
+            store,
+            ssh_key: write_inline(self.ssh_key.as_ref())?,
+            known_hosts: write_inline(self.known_hosts.as_ref())?,
+        })
+    }
+
+    /// Returns the `ssh` command line that applies the SSH settings, or `None` if there are
+    /// none. `files` must come from [`GitCredentialStore::write`] on this store.
+    pub(crate) fn ssh_command(&self, files: &CredentialFiles) -> Option<String> {
+        if self.ssh_key.is_none() && self.known_hosts.is_none() {
+            return None;
+        }
+
+        let mut command = "ssh -o BatchMode=yes".to_owned();
+        if let Some(key) = &self.ssh_key {
+            let path = key.path(files.ssh_key.as_ref());
+            let _ = write!(command, " -o IdentitiesOnly=yes -i {}", shell_quote(path));
+        }
+        if let Some(known_hosts) = &self.known_hosts {
+            let path = known_hosts.path(files.known_hosts.as_ref());
+            let _ = write!(
+                command,
+                " -o UserKnownHostsFile={} -o StrictHostKeyChecking=yes",
+                shell_quote(path),
+            );
+        }
+
+        Some(command)
+    }
+}
+
+impl CredentialFiles {
+    /// The `git credential-store` file, if there are HTTPS credentials.
+    pub(crate) fn store(&self) -> Option<&Path> {
+        self.store.as_ref().map(NamedTempFile::path)
+    }
+}
+
+impl Source {
+    /// The file ssh should read: the given one, or the temporary copy of inline contents.
+    fn path<'a>(&'a self, written: Option<&'a NamedTempFile>) -> &'a Path {
+        match (self, written) {
+            (Self::File(path), _) => path,
+            (Self::Inline(_), Some(file)) => file.path(),
+            (Self::Inline(_), None) => unreachable!("inline contents are always written"),
+        }
+    }
+}
+
+impl std::fmt::Debug for GitCredentialStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct Hosts<'a>(&'a BTreeMap<String, Credential>);
+        impl std::fmt::Debug for Hosts<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_map()
+                    .entries(self.0.iter().map(|(host, c)| (host, &c.username)))
+                    .finish()
+            }
+        }
+
+        f.debug_struct("GitCredentialStore")
+            .field("hosts", &Hosts(&self.hosts))
+            .field("ssh_key", &self.ssh_key)
+            .field("known_hosts", &self.known_hosts)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => f.debug_tuple("File").field(path).finish(),
+            Self::Inline(_) => f.write_str("Inline(..)"),
+        }
+    }
+}
+
+/// Writes inline contents to a temporary file readable only by the current user. A file
+/// source is used as it is and needs none.
+fn write_inline(source: Option<&Source>) -> Result<Option<NamedTempFile>> {
+    let Some(Source::Inline(contents)) = source else {
+        return Ok(None);
+    };
+
+    let mut file = NamedTempFile::new()?;
+    file.write_all(contents.as_bytes())?;
+    // ssh rejects a private key whose last line is not terminated.
+    if !contents.ends_with('\n') {
+        file.write_all(b"\n")?;
+    }
+    file.flush()?;
+
+    Ok(Some(file))
+}
+
+/// Quotes `path` for the POSIX shell git runs `GIT_SSH_COMMAND` and credential helpers with.
+pub(crate) fn shell_quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+/// Encodes everything but RFC 3986 unreserved characters, as `git credential-store` expects.
+fn percent_encode(value: &str) -> String {
+    value.bytes().fold(String::new(), |mut out, byte| {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            let _ = write!(out, "%{byte:02X}");
+        }
+        out
+    })
+}
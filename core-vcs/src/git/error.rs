@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// Errors returned by git operations.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The `git` executable could not be spawned or its output could not be read.
+    #[error("failed to run git: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// A `git` command exited with a non-zero status.
+    #[error("`{command}` failed with {status}: {stderr}")]
+    Command {
+        /// The command line that was executed.
+        command: String,
+        /// The exit status of the process.
+        status: ExitStatus,
+        /// Everything the process wrote to stderr, trimmed.
+        stderr: String,
+    },
+
+    /// A `git` command, or a whole checkout, did not finish in time and was killed.
+    #[error("`{command}` timed out after {timeout:?}")]
+    Timeout {
+        /// The command line that was executed, or the checkout that was cancelled.
+        command: String,
+        /// The time limit that was exceeded.
+        timeout: Duration,
+    },
+
+    /// A patch does not apply to the working tree.
+    #[error("patch {} does not apply: {reason}", .patch.display())]
+    PatchConflict {
+        /// The patch that failed.
+        patch: PathBuf,
+        /// Git's explanation of the conflict.
+        reason: String,
+    },
+
+    /// A quilt `series` file contains an entry that cannot be understood.
+    #[error("invalid entry in {}: `{line}`", .path.display())]
+    InvalidSeries {
+        /// The `series` file.
+        path: PathBuf,
+        /// The offending line.
+        line: String,
+    },
+
+    /// A premirror rule cannot be understood.
+    #[error("invalid mirror rule `{rule}`: {reason}")]
+    InvalidMirrorRule {
+        /// The rule, as `<pattern> <replacement>`.
+        rule: String,
+        /// Why it was rejected.
+        reason: String,
+    },
+
+    /// The given path is not inside a git working tree.
+    #[error("not a git repository: {}", .0.display())]
+    NotARepository(PathBuf),
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::command::GitCommand;
+use super::premirror::{self, MirrorRule};
+use super::{absolute, persist, staging_dir, Error, GitCredentialStore, Result};
+
+/// A directory of bare mirror clones, usable as reference repositories.
+///
+/// Each remote is mirrored at [`RefMirror::path_for`], which follows the naming kas uses for
+/// `kas_repo_ref_dir`. The directory can therefore be handed to kas directly, or a mirror can
+/// be passed to [`Checkout::reference`](super::Checkout::reference).
+#[derive(Debug, Clone)]
+pub struct RefMirror {
+    dir: PathBuf,
+    timeout: Option<Duration>,
+    credentials: Option<GitCredentialStore>,
+    mirrors: Vec<MirrorRule>,
+}
+
+impl RefMirror {
+    /// Uses `dir` as the mirror cache. The directory is created on the first [`sync`].
+    ///
+    /// [`sync`]: RefMirror::sync
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            timeout: None,
+            credentials: None,
+            mirrors: Vec::new(),
+        }
+    }
+
+    /// Gives up on a clone or update that takes longer than `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Clones new mirrors from premirrors when available; see [`RefMirror::sync`].
+    pub fn with_mirrors(mut self, rules: Vec<MirrorRule>) -> Self {
+        self.mirrors = rules;
+        self
+    }
+
+    /// Authenticates requests to HTTPS and SSH remotes with `credentials`.
+    pub fn with_credentials(mut self, credentials: GitCredentialStore) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// The cache directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The location of the mirror of `url`.
+    ///
+    /// Like kas, the host and path of the URL are joined and every `@`, `:`, `/` and `*` is
+    /// replaced by a dot, e.g. `https://git.yoctoproject.org/poky` becomes
+    /// `git.yoctoproject.org.poky`.
+    pub fn path_for(&self, url: &str) -> PathBuf {
+        let location = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let name: String = location
+            .chars()
+            .map(|c| if "@:/*".contains(c) { '.' } else { c })
+            .collect();
+
+        self.dir.join(name)
+    }
+
+    /// Creates the mirror of `url`, or brings an existing one up to date.
+    ///
+    /// Refs deleted upstream are pruned from an existing mirror. Anything else found at the
+    /// mirror's path, such as a directory that is not a bare repository, is replaced by a fresh
+    /// clone; git is never run inside it, where it would find an enclosing repository instead.
+    /// A new mirror is cloned from the first reachable premirror given to
+    /// [`RefMirror::with_mirrors`], falling back to `url`. It is cloned next to its final
+    /// location and moved into place once complete, so an interrupted clone leaves nothing
+    /// behind, and of two concurrent syncs of the same URL the first to finish wins. Returns
+    /// the mirror's absolute path.
+    pub async fn sync(&self, url: &str) -> Result<PathBuf> {
+        let path = absolute(&self.path_for(url))?;
+        if is_bare_repository(&path) {
+            GitCommand::new()
+                .timeout(self.timeout)
+                .credentials(self.credentials.as_ref())?
+                .arg(git_dir(&path))
+                .args(["fetch", "--quiet", "--prune", "origin"])
+                .output()
+                .await?;
+            return Ok(path);
+        }
+
+        if path.is_dir() {
+            tokio::fs::remove_dir_all(&path).await?;
+        } else if path.exists() {
+            tokio::fs::remove_file(&path).await?;
+        }
+        let credentials = self.credentials.as_ref();
+        if let Some(mirror) =
+            premirror::reachable(&self.mirrors, url, self.timeout, credentials).await
+        {
+            match self.clone(&mirror, url, &path).await {
+                Err(Error::Command { .. } | Error::Timeout { .. }) => {}
+                result => return result.map(|()| path),
+            }
+        }
+        self.clone(url, url, &path).await?;
+
+        Ok(path)
+    }
+
+    /// Clones `source` as the mirror of `url` at `path`, with `origin` pointing at `url`.
+    async fn clone(&self, source: &str, url: &str, path: &Path) -> Result<()> {
+        let staging = staging_dir(path).await?;
+        let staged = staging.path().join("mirror.git");
+        GitCommand::new()
+            .timeout(self.timeout)
+            .credentials(self.credentials.as_ref())?
+            .args(["clone", "--quiet", "--mirror", "--"])
+            .arg(source)
+            .arg(&staged)
+            .output()
+            .await?;
+        if source != url {
+            GitCommand::new()
+                .arg(git_dir(&staged))
+                .args(["remote", "set-url", "origin", "--"])
+                .arg(url)
+                .output()
+                .await?;
+        }
+
+        match persist(&staged, path).await {
+            // Another sync of the same URL got there first; its mirror is just as fresh.
+            Err(_) if is_bare_repository(path) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Removes the mirrors of every URL not in `keep` and compacts the ones that remain.
+    ///
+    /// Only directories that are bare git repositories are considered, so unrelated files in
+    /// the cache directory are left alone. Staging directories left behind by a [`sync`] that
+    /// was killed mid-clone are swept as well, so `gc` must not run while a sync of the same
+    /// cache is in progress. The kept mirrors are compacted with `git gc --auto` once all
+    /// removals are done; if compacting any of them fails, the others are still compacted and
+    /// the first error is returned. Returns the absolute paths of the removed mirrors,
+    /// comparable with the paths returned by [`RefMirror::sync`].
+    ///
+    /// [`sync`]: RefMirror::sync
+    pub async fn gc(&self, keep: &[&str]) -> Result<Vec<PathBuf>> {
+        let dir = absolute(&self.dir)?;
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let keep = keep
+            .iter()
+            .map(|url| absolute(&self.path_for(url)))
+            .collect::<Result<BTreeSet<_>>>()?;
+        let mut kept = Vec::new();
+        let mut removed = Vec::new();
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if !is_bare_repository(&path) {
+                if is_staging_dir(&path) {
+                    tokio::fs::remove_dir_all(&path).await?;
+                }
+                continue;
+            }
+
+            if keep.contains(&path) {
+                kept.push(path);
+            } else {
+                tokio::fs::remove_dir_all(&path).await?;
+                removed.push(path);
+            }
+        }
+        removed.sort();
+
+        let mut failure = None;
+        for path in &kept {
+            let compacted = GitCommand::new()
+                .arg(git_dir(path))
+                .args(["gc", "--auto", "--quiet"])
+                .output()
+                .await;
+            if let Err(err) = compacted {
+                failure.get_or_insert(err);
+            }
+        }
+
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(removed),
+        }
+    }
+}
+
+/// Returns `--git-dir=<path>`, which makes git use the mirror at `path` without searching the
+/// directories above it for a repository.
+fn git_dir(path: &Path) -> OsString {
+    let mut arg = OsString::from("--git-dir=");
+    arg.push(path);
+    arg
+}
+
+/// Returns `true` for the hidden directories [`staging_dir`] creates next to a mirror.
+fn is_staging_dir(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.starts_with('.') && path.is_dir()
+}
+
+fn is_bare_repository(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
@@ -0,0 +1,68 @@
+//! Asynchronous wrappers around the `git` command-line tool.
+
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+
+pub use checkout::{Checkout, Revision};
+pub use commit::Commit;
+pub use credentials::GitCredentialStore;
+pub use error::Error;
+pub use mirror::RefMirror;
+pub use patch::{PatchOutcome, PatchStatus};
+pub use premirror::MirrorRule;
+pub use remote::{Remote, RemoteRef};
+pub use repository::Repository;
+pub use state::RepoState;
+
+mod checkout;
+mod command;
+mod commit;
+mod credentials;
+mod error;
+mod mirror;
+mod patch;
+mod premirror;
+mod remote;
+mod repository;
+mod state;
+
+/// A specialized [`Result`](std::result::Result) type for git operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Resolves `path` against the process working directory, since git would otherwise resolve it
+/// against the directory it runs in.
+fn absolute(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.to_path_buf())
+    } else {
+        Ok(std::env::current_dir()?.join(path))
+    }
+}
+
+/// Creates an empty directory next to `dest` for cloning into.
+///
+/// Cloning into a staging directory and moving the result into place with [`persist`] means an
+/// interrupted or timed-out clone never leaves a partial repository at `dest`: the staging
+/// directory is removed when the returned handle is dropped.
+async fn staging_dir(dest: &Path) -> Result<TempDir> {
+    let parent = dest.parent().unwrap_or_else(|| Path::new("/"));
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    tokio::fs::create_dir_all(parent).await?;
+
+    Ok(tempfile::Builder::new()
+        .prefix(&format!(".{name}."))
+        .tempdir_in(parent)?)
+}
+
+/// Moves a repository cloned into a staging directory to `dest`.
+///
+/// `dest` may already exist as an empty directory; any other existing `dest` is an error.
+async fn persist(staged: &Path, dest: &Path) -> Result<()> {
+    if dest.is_dir() {
+        tokio::fs::remove_dir(dest).await?;
+    }
+    tokio::fs::rename(staged, dest).await?;
+
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+
+use super::{Error, Repository, Result};
+
+/// What happened to one patch in [`Repository::apply_patches`](super::Repository::apply_patches).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchOutcome {
+    /// The patch file.
+    pub patch: PathBuf,
+    /// Whether the patch was applied or found already applied.
+    pub status: PatchStatus,
+}
+
+/// The status of a patch after it has been processed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchStatus {
+    /// The patch was applied (or, in a dry run, would apply cleanly).
+    Applied,
+    /// The patch and all patches before it were already applied, so it was skipped.
+    AlreadyApplied,
+}
+
+/// The name patch commits are authored and committed under.
+///
+/// kas commits patches under the same identity, so a layer patched by either tool is recognised
+/// by the other.
+const IDENTITY_NAME: &str = "kas";
+/// The email address patch commits are authored and committed under.
+const IDENTITY_EMAIL: &str = "kas@example.com";
+
+/// A patch file together with its path-strip level (`git apply -p`).
+#[derive(Debug)]
+pub(crate) struct PatchFile {
+    pub(crate) path: PathBuf,
+    pub(crate) strip: u32,
+}
+
+/// Expands `patches` into individual patch files.
+///
+/// A directory is treated as a quilt patch directory: its `series` file lists the patches in
+/// order, one per line, optionally followed by a `-pN` strip level. Blank lines and `#`
+/// comments are ignored. A patch file that does not exist is reported as an
+/// [`io::ErrorKind::NotFound`] error rather than as a conflict.
+pub(crate) async fn expand(patches: &[impl AsRef<Path>]) -> Result<Vec<PatchFile>> {
+    let mut files = Vec::new();
+    for patch in patches {
+        let patch = patch.as_ref();
+        if !patch.is_dir() {
+            files.push(PatchFile {
+                path: patch.to_path_buf(),
+                strip: 1,
+            });
+            continue;
+        }
+
+        let series = patch.join("series");
+        let contents = tokio::fs::read_to_string(&series).await?;
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let Some(name) = words.next() else {
+                continue;
+            };
+
+            let mut strip = 1;
+            for option in words {
+                strip = option
+                    .strip_prefix("-p")
+                    .and_then(|level| level.parse().ok())
+                    .ok_or_else(|| Error::InvalidSeries {
+                        path: series.clone(),
+                        line: line.trim().to_owned(),
+                    })?;
+            }
+            files.push(PatchFile {
+                path: patch.join(name),
+                strip,
+            });
+        }
+    }
+
+    if let Some(missing) = files.iter().find(|file| !file.path.is_file()) {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("patch {} not found", missing.path.display()),
+        )));
+    }
+
+    Ok(files)
+}
+
+/// A throwaway copy of a repository's index, for use through `GIT_INDEX_FILE`.
+pub(crate) struct ScratchIndex {
+    dir: TempDir,
+}
+
+impl ScratchIndex {
+    /// Copies the index of `repo`. A repository without an index yet (nothing was ever staged)
+    /// gets an empty one: git treats a missing index file as empty.
+    pub(crate) async fn new(repo: &Repository) -> Result<Self> {
+        let index = repo
+            .git()
+            .args(["rev-parse", "--git-path", "index"])
+            .output()
+            .await?;
+        let index = repo.path().join(index.trim_end());
+        let scratch = Self {
+            dir: TempDir::new()?,
+        };
+        if index.is_file() {
+            tokio::fs::copy(&index, scratch.path()).await?;
+        }
+
+        Ok(scratch)
+    }
+
+    pub(crate) fn path(&self) -> PathBuf {
+        self.dir.path().join("index")
+    }
+}
+
+/// Returns how many patches at the start of `patches` are already applied.
+///
+/// Patches in a set may build on each other, so a single patch cannot be checked in isolation:
+/// once its successors are applied it no longer reverses cleanly on its own. Instead, the
+/// longest prefix whose patches can be reverse-applied one after another, last first, is
+/// looked for on a scratch index.
+pub(crate) async fn applied_prefix(repo: &Repository, patches: &[PatchFile]) -> Result<usize> {
+    'prefix: for len in (1..=patches.len()).rev() {
+        let index = ScratchIndex::new(repo).await?;
+        for patch in patches[..len].iter().rev() {
+            match apply(repo, patch, &["--cached", "--reverse"], Some(&index.path())).await {
+                Ok(()) => {}
+                Err(Error::PatchConflict { .. }) => continue 'prefix,
+                Err(err) => return Err(err),
+            }
+        }
+        return Ok(len);
+    }
+
+    Ok(0)
+}
+
+/// Runs `git apply <args> <patch>`, optionally against the index file at `index`.
+pub(crate) async fn apply(
+    repo: &Repository,
+    patch: &PatchFile,
+    args: &[&str],
+    index: Option<&Path>,
+) -> Result<()> {
+    let mut cmd = repo.git();
+    if let Some(index) = index {
+        cmd = cmd.env("GIT_INDEX_FILE", index);
+    }
+
+    cmd.arg("apply")
+        .args(args)
+        .arg(format!("-p{}", patch.strip))
+        .arg("--")
+        .arg(&patch.path)
+        .output()
+        .await
+        .map(drop)
+        .map_err(|err| match err {
+            Error::Command { stderr, .. } => Error::PatchConflict {
+                patch: patch.path.clone(),
+                reason: stderr,
+            },
+            err => err,
+        })
+}
+
+/// Commits what applying `patch` staged, under the fixed patch identity.
+///
+/// Hooks and commit signing are skipped: the commit records a build input, not the user's work.
+pub(crate) async fn commit(repo: &Repository, patch: &PatchFile) -> Result<()> {
+    let name = patch.path.file_name().unwrap_or_default().to_string_lossy();
+    repo.git()
+        .env("GIT_AUTHOR_NAME", IDENTITY_NAME)
+        .env("GIT_AUTHOR_EMAIL", IDENTITY_EMAIL)
+        .env("GIT_COMMITTER_NAME", IDENTITY_NAME)
+        .env("GIT_COMMITTER_EMAIL", IDENTITY_EMAIL)
+        .args([
+            "-c",
+            "commit.gpgSign=false",
+            "commit",
+            "--quiet",
+            "--no-verify",
+        ])
+        .args(["--allow-empty", "-m"])
+        .arg(format!("Apply {name}"))
+        .output()
+        .await
+        .map(drop)
+}
+
+/// Returns the commit `HEAD` was patched on top of.
+///
+/// Starting at `HEAD`, commits made by [`commit`] are skipped until one that was not is found.
+/// Without any patch commits this is `HEAD` itself.
+pub(crate) async fn unpatched_head(repo: &Repository) -> Result<String> {
+    let mut rev = repo.head().await?;
+    loop {
+        let stdout = repo
+            .git()
+            .args(["log", "-1", "--format=%P%x00%ae%x00%ce", &rev, "--"])
+            .output()
+            .await?;
+        let mut fields = stdout.trim_end().split('\0');
+        let parents: Vec<_> = fields
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        let by_patch = fields.all(|email| email == IDENTITY_EMAIL);
+        match parents[..] {
+            [parent] if by_patch => rev = parent.to_owned(),
+            _ => return Ok(rev),
+        }
+    }
+}
+
+/// Reports the first `applied` patches as already applied and the rest as applied now.
+pub(crate) fn outcomes(patches: Vec<PatchFile>, applied: usize) -> Vec<PatchOutcome> {
+    patches
+        .into_iter()
+        .enumerate()
+        .map(|(i, file)| PatchOutcome {
+            patch: file.path,
+            status: if i < applied {
+                PatchStatus::AlreadyApplied
+            } else {
+                PatchStatus::Applied
+            },
+        })
+        .collect()
+}
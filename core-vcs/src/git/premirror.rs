@@ -0,0 +1,161 @@
+use std::fmt;
+use std::time::Duration;
+
+use regex::Regex;
+
+use super::{Error, GitCredentialStore, Remote, Result};
+
+/// A rule that maps a repository URL to a premirror, in the format of kas's `KAS_PREMIRRORS`.
+///
+/// A rule applies to a URL when its pattern matches at the start of the URL, like Python's
+/// `re.match`. Every match is then replaced, like `re.sub`. The replacement refers to capture
+/// groups with `\1` or `\g<name>`, as in Python, so a rule written for kas works unchanged as
+/// long as its pattern needs no look-around, which the [`regex`] crate does not support.
+///
+/// ```
+/// use core_vcs::git::MirrorRule;
+///
+/// let premirrors = "https://git.yoctoproject.org/(.*) file:///srv/mirrors/\\1\n";
+/// let rules = MirrorRule::parse_all(premirrors)?;
+///
+/// assert_eq!(
+///     rules[0].apply("https://git.yoctoproject.org/poky").as_deref(),
+///     Some("file:///srv/mirrors/poky")
+/// );
+/// # Ok::<(), core_vcs::git::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct MirrorRule {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl MirrorRule {
+    /// Creates a rule that rewrites URLs matching `pattern` to `replacement`.
+    ///
+    /// Fails with [`Error::InvalidMirrorRule`] if `pattern` is not a valid regular expression.
+    pub fn new(pattern: &str, replacement: impl Into<String>) -> Result<Self> {
+        let replacement = replacement.into();
+        let pattern = Regex::new(pattern).map_err(|err| Error::InvalidMirrorRule {
+            rule: format!("{pattern} {replacement}"),
+            reason: err.to_string(),
+        })?;
+
+        Ok(Self {
+            pattern,
+            replacement,
+        })
+    }
+
+    /// Parses rules in `KAS_PREMIRRORS` format: one `<pattern> <replacement>` pair per line.
+    ///
+    /// Blank lines are skipped. Joining the [`Display`](fmt::Display) output of the returned
+    /// rules with newlines gives the list back, ready to be exported for kas.
+    pub fn parse_all(rules: &str) -> Result<Vec<Self>> {
+        rules
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next(), words.next()) {
+                    (Some(pattern), Some(replacement), None) => Self::new(pattern, replacement),
+                    _ => Err(Error::InvalidMirrorRule {
+                        rule: line.trim().to_owned(),
+                        reason: "expected a pattern and a replacement".to_owned(),
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the premirror URL for `url`, or `None` if the rule does not apply to it.
+    pub fn apply(&self, url: &str) -> Option<String> {
+        let found = self.pattern.find(url)?;
+        if found.start() != 0 {
+            return None;
+        }
+
+        let replacement = python_to_regex_replacement(&self.replacement);
+        Some(
+            self.pattern
+                .replace_all(url, replacement.as_str())
+                .into_owned(),
+        )
+    }
+}
+
+impl PartialEq for MirrorRule {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern.as_str() == other.pattern.as_str() && self.replacement == other.replacement
+    }
+}
+
+impl Eq for MirrorRule {}
+
+impl fmt::Display for MirrorRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.pattern.as_str(), self.replacement)
+    }
+}
+
+/// Returns the first premirror of `url` under `rules` that answers a probe, if any.
+///
+/// Mirrors that fail the probe, by error or by timeout, are skipped, so a dead mirror costs at
+/// most one `timeout` before the next candidate or upstream is tried.
+pub(crate) async fn reachable(
+    rules: &[MirrorRule],
+    url: &str,
+    timeout: Option<Duration>,
+    credentials: Option<&GitCredentialStore>,
+) -> Option<String> {
+    for mirror in rules.iter().filter_map(|rule| rule.apply(url)) {
+        let mut remote = Remote::new(mirror.as_str());
+        if let Some(timeout) = timeout {
+            remote = remote.with_timeout(timeout);
+        }
+        if let Some(credentials) = credentials {
+            remote = remote.with_credentials(credentials.clone());
+        }
+        if remote.probe().await.is_ok() {
+            return Some(mirror);
+        }
+    }
+
+    None
+}
+
+/// Translates a Python `re.sub` replacement into the syntax of [`Regex::replace_all`].
+///
+/// `\1` and `\g<name>` become group references, `\\` a backslash, and a literal `$` is
+/// escaped.
+fn python_to_regex_replacement(replacement: &str) -> String {
+    let mut out = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => out.push_str("$$"),
+            '\\' => match chars.peek() {
+                Some(digit) if digit.is_ascii_digit() => {
+                    let mut group = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        group.push(digit);
+                    }
+                    out.push_str(&format!("${{{group}}}"));
+                }
+                Some('g') => {
+                    chars.next();
+                    let name: String = chars.by_ref().skip(1).take_while(|&c| c != '>').collect();
+                    out.push_str(&format!("${{{name}}}"));
+                }
+                Some('\\') => {
+                    chars.next();
+                    out.push('\\');
+                }
+                _ => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+
+    out
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use super::command::GitCommand;
+use super::{GitCredentialStore, Result};
+
+/// A remote repository, addressed by URL, that has not necessarily been cloned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    url: String,
+    timeout: Option<Duration>,
+    credentials: Option<GitCredentialStore>,
+}
+
+/// A ref advertised by a [`Remote`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRef {
+    /// The full ref name, e.g. `refs/heads/kirkstone`.
+    pub name: String,
+    /// The commit hash the ref points to.
+    pub id: String,
+}
+
+impl Remote {
+    /// Creates a handle for the repository at `url`.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            timeout: None,
+            credentials: None,
+        }
+    }
+
+    /// Gives up on any request to the remote that takes longer than `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Authenticates requests to an HTTPS or SSH remote with `credentials`.
+    pub fn with_credentials(mut self, credentials: GitCredentialStore) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// The URL of the remote.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Lists the refs advertised by the remote (`git ls-remote`).
+    ///
+    /// When `patterns` is non-empty only refs matching one of them are returned, using the same
+    /// tail-matching rules as `git ls-remote <url> <patterns>...`.
+    pub async fn list_refs(&self, patterns: &[&str]) -> Result<Vec<RemoteRef>> {
+        let stdout = GitCommand::new()
+            .timeout(self.timeout)
+            .credentials(self.credentials.as_ref())?
+            .args(["ls-remote", "--end-of-options", &self.url])
+            .args(patterns)
+            .output()
+            .await?;
+
+        Ok(stdout
+            .lines()
+            .filter_map(|line| {
+                let (id, name) = line.split_once('\t')?;
+                Some(RemoteRef {
+                    name: name.to_owned(),
+                    id: id.to_owned(),
+                })
+            })
+            .collect())
+    }
+
+    /// Resolves the current tip of `branch` on the remote without fetching it.
+    ///
+    /// Returns `None` if the remote has no such branch.
+    pub async fn branch_head(&self, branch: &str) -> Result<Option<String>> {
+        let name = format!("refs/heads/{branch}");
+        let refs = self.list_refs(&[&name]).await?;
+
+        Ok(refs.into_iter().find(|r| r.name == name).map(|r| r.id))
+    }
+
+    /// Checks that the remote is reachable and serves a git repository.
+    ///
+    /// Only the remote `HEAD` is requested, so this is cheap even for large repositories.
+    pub async fn probe(&self) -> Result<()> {
+        self.list_refs(&["HEAD"]).await.map(drop)
+    }
+}
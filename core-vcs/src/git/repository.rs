@@ -0,0 +1,269 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::command::GitCommand;
+use super::patch::{self, PatchOutcome, ScratchIndex};
+use super::{absolute, Commit, Error, GitCredentialStore, Result};
+
+/// A git working tree on the local filesystem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repository {
+    path: PathBuf,
+    credentials: Option<GitCredentialStore>,
+    timeout: Option<Duration>,
+}
+
+impl Repository {
+    /// Opens the working tree containing `path`.
+    ///
+    /// `path` may point anywhere inside the working tree; the repository is rooted at its
+    /// top-level directory.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return Err(Error::NotARepository(path.to_path_buf()));
+        }
+
+        let toplevel = GitCommand::new()
+            .current_dir(path)
+            .args(["rev-parse", "--show-toplevel"])
+            .output()
+            .await
+            .map_err(|err| match err {
+                Error::Command { .. } => Error::NotARepository(path.to_path_buf()),
+                err => err,
+            })?;
+
+        Ok(Self {
+            path: PathBuf::from(toplevel.trim_end()),
+            credentials: None,
+            timeout: None,
+        })
+    }
+
+    /// Opens the repository whose top-level directory is `path` itself.
+    ///
+    /// Unlike [`Repository::open`], a directory nested inside some other working tree (say, an
+    /// empty `layers/poky` in the user's project) is reported as [`Error::NotARepository`]
+    /// rather than resolving to the enclosing repository.
+    pub async fn open_exact(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let repo = Self::open(path).await?;
+        if tokio::fs::canonicalize(path).await? != tokio::fs::canonicalize(&repo.path).await? {
+            return Err(Error::NotARepository(path.to_path_buf()));
+        }
+
+        Ok(repo)
+    }
+
+    /// Authenticates fetches from HTTPS and SSH remotes with `credentials`.
+    pub fn with_credentials(mut self, credentials: GitCredentialStore) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Gives up on fetches that take longer than `timeout`, returning [`Error::Timeout`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The top-level directory of the working tree.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The hash of the commit currently checked out.
+    pub async fn head(&self) -> Result<String> {
+        self.rev_parse("HEAD").await
+    }
+
+    /// Returns `true` if any tracked file has uncommitted changes, staged or not.
+    ///
+    /// Untracked files are ignored, since build output routinely lands inside layer checkouts.
+    pub async fn is_dirty(&self) -> Result<bool> {
+        let stdout = self
+            .git()
+            .args(["status", "--porcelain", "--untracked-files=no"])
+            .output()
+            .await?;
+
+        Ok(!stdout.is_empty())
+    }
+
+    /// Checks out `rev` as a detached `HEAD`.
+    ///
+    /// `rev` may be anything [`Repository::rev_parse`] accepts; it is resolved to a commit
+    /// first, so it is never mistaken for an option. Like `git checkout --detach`, this refuses
+    /// to overwrite local modifications that conflict with the target commit.
+    pub async fn checkout(&self, rev: &str) -> Result<()> {
+        let commit = self.rev_parse(rev).await?;
+        self.git()
+            .args(["checkout", "--quiet", "--detach", &commit, "--"])
+            .output()
+            .await
+            .map(drop)
+    }
+
+    /// Updates the remote-tracking branches from `origin`.
+    pub async fn fetch(&self) -> Result<()> {
+        self.fetch_with(None, None).await
+    }
+
+    /// Fetches `refspec` (or the configured refspecs) from `origin`, optionally shallow.
+    pub(crate) async fn fetch_with(&self, refspec: Option<&str>, depth: Option<u32>) -> Result<()> {
+        let mut fetch = self
+            .git()
+            .timeout(self.timeout)
+            .credentials(self.credentials.as_ref())?
+            .args(["fetch", "--quiet"]);
+        if let Some(depth) = depth {
+            fetch = fetch.arg(format!("--depth={depth}"));
+        }
+        fetch.arg("origin").args(refspec).output().await.map(drop)
+    }
+
+    /// Resolves `rev` to the full hash of the commit it points to.
+    ///
+    /// Accepts anything `git rev-parse` does: branch names, tags, remote-tracking refs such as
+    /// `origin/kirkstone`, or abbreviated hashes.
+    pub async fn rev_parse(&self, rev: &str) -> Result<String> {
+        let stdout = self
+            .git()
+            .args(["rev-parse", "--verify", "--end-of-options"])
+            .arg(format!("{rev}^{{commit}}"))
+            .output()
+            .await?;
+
+        Ok(stdout.trim_end().to_owned())
+    }
+
+    /// Lists the commits reachable from `to` but not from `from`, newest first.
+    ///
+    /// This is the shortlog of moving a pin from `from` to `to`.
+    pub async fn log(&self, from: &str, to: &str) -> Result<Vec<Commit>> {
+        let stdout = self
+            .git()
+            .args(["log", Commit::FORMAT, "--end-of-options"])
+            .arg(format!("{from}..{to}"))
+            .output()
+            .await?;
+
+        Ok(stdout.lines().filter_map(Commit::parse).collect())
+    }
+
+    /// Lists the tracked files that differ between `since` and the working tree.
+    ///
+    /// Both committed and uncommitted changes are included. Renames are reported as a deletion
+    /// plus an addition, so the old and the new path both appear. Paths are relative to the
+    /// top-level directory.
+    pub async fn changed_files(&self, since: &str) -> Result<Vec<PathBuf>> {
+        let stdout = self
+            .git()
+            .args(["diff", "--name-only", "--no-renames", "-z"])
+            .args(["--end-of-options", since, "--"])
+            .output()
+            .await?;
+
+        Ok(stdout
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .collect())
+    }
+
+    /// Exports the commits between `base` and `HEAD` as numbered patch files in `output_dir`.
+    ///
+    /// This is `git format-patch <base>..HEAD`, with the commit hashes zeroed and the signature
+    /// dropped so that re-exporting an unchanged series yields byte-identical files. The
+    /// directory is created if needed. Numbered patches left over from a previous export
+    /// (`NNNN-*.patch`) are removed first, so the directory always holds exactly the current
+    /// series; other files are kept. Returns the paths of the written patches, in order.
+    pub async fn format_patch(
+        &self,
+        base: &str,
+        output_dir: impl AsRef<Path>,
+    ) -> Result<Vec<PathBuf>> {
+        let output_dir = absolute(output_dir.as_ref())?;
+        if output_dir.is_dir() {
+            let mut entries = tokio::fs::read_dir(&output_dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name();
+                if is_numbered_patch(&name.to_string_lossy()) && entry.file_type().await?.is_file()
+                {
+                    tokio::fs::remove_file(entry.path()).await?;
+                }
+            }
+        }
+
+        let stdout = self
+            .git()
+            .args(["format-patch", "--zero-commit", "--no-signature", "-o"])
+            .arg(&output_dir)
+            .arg("--end-of-options")
+            .arg(format!("{base}..HEAD"))
+            .output()
+            .await?;
+
+        Ok(stdout.lines().map(PathBuf::from).collect())
+    }
+
+    /// Applies `patches` in order, committing each one on top of `HEAD`.
+    ///
+    /// Each entry is either a patch file or a quilt patch directory with a `series` file.
+    /// Patches at the start of the list whose changes are already present are skipped, so
+    /// applying the same set twice is harmless. The first patch that does not apply stops the
+    /// run with [`Error::PatchConflict`]; the patches before it stay applied.
+    ///
+    /// Like kas, every patch becomes a commit authored and committed by
+    /// `kas <kas@example.com>`. The working tree stays clean, so moving the checkout to another
+    /// revision later just works, and [`RepoState::inspect`](super::RepoState::inspect) still
+    /// recognises the pinned commit beneath the patches. Changes that were already staged are
+    /// committed along with the first patch, so apply patches to a clean tree.
+    pub async fn apply_patches(&self, patches: &[impl AsRef<Path>]) -> Result<Vec<PatchOutcome>> {
+        let patches = patch::expand(patches).await?;
+        let applied = patch::applied_prefix(self, &patches).await?;
+        for file in &patches[applied..] {
+            patch::apply(self, file, &["--index"], None).await?;
+            patch::commit(self, file).await?;
+        }
+
+        Ok(patch::outcomes(patches, applied))
+    }
+
+    /// Checks whether `patches` would apply, without touching the working tree, the index or
+    /// `HEAD`.
+    ///
+    /// This is the dry-run counterpart of [`Repository::apply_patches`] and reports the same
+    /// outcomes and conflicts. The patches are applied to a scratch copy of the index, so a
+    /// patch that builds on an earlier one in the same set is checked correctly.
+    ///
+    /// Because the check runs against the index, it does not see uncommitted changes in the
+    /// working tree. `apply_patches` also requires the working tree to match the index for
+    /// every file a patch touches, so on a dirty tree a patch reported here as
+    /// [`PatchStatus::Applied`](super::PatchStatus::Applied) may still be rejected by the real
+    /// run. Check [`Repository::is_dirty`] first when that matters.
+    pub async fn check_patches(&self, patches: &[impl AsRef<Path>]) -> Result<Vec<PatchOutcome>> {
+        let patches = patch::expand(patches).await?;
+        let applied = patch::applied_prefix(self, &patches).await?;
+        let index = ScratchIndex::new(self).await?;
+        for file in &patches[applied..] {
+            patch::apply(self, file, &["--cached"], Some(&index.path())).await?;
+        }
+
+        Ok(patch::outcomes(patches, applied))
+    }
+
+    pub(crate) fn git(&self) -> GitCommand {
+        GitCommand::new().current_dir(&self.path)
+    }
+}
+
+/// Matches the file names `git format-patch` writes: four digits, a dash, and `.patch`.
+fn is_numbered_patch(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    bytes.len() > 11
+        && bytes[..4].iter().all(u8::is_ascii_digit)
+        && bytes[4] == b'-'
+        && name.ends_with(".patch")
+}
@@ -0,0 +1,74 @@
+use std::path::Path;
+
+use super::{patch, Error, Repository, Result};
+
+/// The state of a repository's working tree relative to the commit it is pinned to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepoState {
+    /// There is no repository rooted at the expected path.
+    Missing,
+    /// A repository exists but has no commit checked out, as left by an interrupted clone.
+    Unborn,
+    /// The repository is checked out.
+    CheckedOut {
+        /// The commit `HEAD` points to.
+        head: String,
+        /// Whether tracked files have uncommitted modifications.
+        dirty: bool,
+        /// Whether the pinned commit is checked out, or `None` if no pin was given.
+        ///
+        /// Patch commits made by [`Repository::apply_patches`] on top of the pin still count
+        /// as the pin.
+        pinned: Option<bool>,
+    },
+}
+
+impl RepoState {
+    /// Inspects the working tree at `path`, comparing its `HEAD` with `pin` if one is given.
+    ///
+    /// Only a repository whose top-level directory is `path` itself counts; a directory
+    /// nested inside another working tree is [`RepoState::Missing`]. `pin` may be anything
+    /// [`Repository::rev_parse`] accepts. A pin that does not resolve in the local repository
+    /// counts as a mismatch, since the pinned commit cannot be checked out. Before comparing,
+    /// patch commits made by [`Repository::apply_patches`] are peeled off `HEAD`, so a layer
+    /// that was checked out at its pin and then patched is still in sync.
+    pub async fn inspect(path: impl AsRef<Path>, pin: Option<&str>) -> Result<Self> {
+        let repo = match Repository::open_exact(path).await {
+            Ok(repo) => repo,
+            Err(Error::NotARepository(_)) => return Ok(Self::Missing),
+            Err(err) => return Err(err),
+        };
+
+        let head = match repo.head().await {
+            Ok(head) => head,
+            Err(Error::Command { .. }) => return Ok(Self::Unborn),
+            Err(err) => return Err(err),
+        };
+        let pinned = match pin {
+            Some(pin) => Some(match repo.rev_parse(pin).await {
+                Ok(commit) => commit == patch::unpatched_head(&repo).await?,
+                Err(Error::Command { .. }) => false,
+                Err(err) => return Err(err),
+            }),
+            None => None,
+        };
+
+        Ok(Self::CheckedOut {
+            dirty: repo.is_dirty().await?,
+            head,
+            pinned,
+        })
+    }
+
+    /// Returns `true` if the repository is checked out, clean and at its pin (if any).
+    pub fn is_in_sync(&self) -> bool {
+        matches!(
+            self,
+            Self::CheckedOut {
+                dirty: false,
+                pinned: None | Some(true),
+                ..
+            }
+        )
+    }
+}
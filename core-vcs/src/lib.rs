@@ -1 +1,6 @@
+//! Version control primitives for managing layer repositories.
+//!
+//! The [`git`] module drives the `git` command-line tool through [`tokio::process`], so every
+//! operation is asynchronous and behaves exactly like the user's own git installation.
 
+pub mod git;